@@ -8,7 +8,7 @@ use std::{
 use cfgen::{prelude::*, ConfigLoad};
 use rayon::prelude::*;
 use structopt::StructOpt;
-use tgcd::{client::TgcdClient, Blake2bHash, Tag};
+use tgcd::{client::TgcdClient, Blake2bHash, HashKind, Tag};
 use thiserror::Error;
 
 const DEFAULT_CONFIG: &str = include_str!("../../default_config.toml");
@@ -19,6 +19,16 @@ const DEFAULT_CONFIG: &str = include_str!("../../default_config.toml");
 struct Config {
     server_url: String,
     max_cores: usize,
+    #[serde(default)]
+    hash_kind: HashKind,
+    #[serde(default = "default_prefix_len")]
+    prefix_len: u64,
+    #[serde(default)]
+    token: Option<String>,
+}
+
+fn default_prefix_len() -> u64 {
+    tgcd::DEFAULT_PREFIX_LEN
 }
 
 #[derive(StructOpt)]
@@ -33,9 +43,23 @@ struct Opt {
 #[derive(StructOpt)]
 enum Subcmd {
     AddFileTags { file: PathBuf, tags: Vec<String> },
+    AddFilesTags {
+        #[structopt(long = "file")]
+        files: Vec<PathBuf>,
+
+        #[structopt(long = "tag")]
+        tags: Vec<String>,
+    },
     GetFileTags { file: PathBuf },
     GetFilesTags { files: Vec<String> },
     CopyTags { src: PathBuf, dest: PathBuf },
+    FindFiles {
+        #[structopt(long = "tag")]
+        tags: Vec<String>,
+
+        #[structopt(long)]
+        match_all: bool,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -70,6 +94,7 @@ trait Output {
         tag_map: &HashMap<String, Vec<String>>,
         out: &mut dyn io::Write,
     ) -> Result<(), io::Error>;
+    fn hashes(&self, hashes: &[String], out: &mut dyn io::Write) -> Result<(), io::Error>;
 }
 
 struct Json;
@@ -88,6 +113,11 @@ impl Output for Json {
         let s = serde_json::to_string(tag_map).unwrap();
         out.write(s.as_bytes()).map(|_| ())
     }
+
+    fn hashes(&self, hashes: &[String], out: &mut dyn io::Write) -> Result<(), io::Error> {
+        let s = serde_json::to_string(hashes).unwrap();
+        out.write(s.as_bytes()).map(|_| ())
+    }
 }
 
 struct Human;
@@ -100,6 +130,13 @@ impl Output for Human {
         Ok(())
     }
 
+    fn hashes(&self, hashes: &[String], out: &mut dyn io::Write) -> Result<(), io::Error> {
+        for hash in hashes {
+            writeln!(out, "{}", hash)?;
+        }
+        Ok(())
+    }
+
     fn files_tags(
         &self,
         tag_map: &HashMap<String, Vec<String>>,
@@ -115,8 +152,15 @@ impl Output for Human {
     }
 }
 
-fn try_hash(path: PathBuf) -> Result<Blake2bHash, Error> {
-    Blake2bHash::from_file(&path).map_err(|e| Error::Hash { e, path })
+fn try_hash(path: PathBuf, kind: HashKind) -> Result<Blake2bHash, Error> {
+    Blake2bHash::from_file_with_kind(&path, kind).map_err(|e| Error::Hash { e, path })
+}
+
+fn try_hash_prefix(path: &Path, limit: u64, kind: HashKind) -> Result<Blake2bHash, Error> {
+    Blake2bHash::from_file_prefix_with_kind(path, limit, kind).map_err(|e| Error::Hash {
+        e,
+        path: path.to_owned(),
+    })
 }
 
 async fn run() -> Result<(), Error> {
@@ -131,9 +175,13 @@ async fn run() -> Result<(), Error> {
         .build_global()
         .unwrap();
 
-    let mut client = TgcdClient::connect(cfg.server_url)
+    let client = TgcdClient::connect(cfg.server_url)
         .await
         .map_err(Error::RpcConnect)?;
+    let mut client = match cfg.token {
+        Some(token) => client.with_token(token),
+        None => client,
+    };
 
     let output: Box<dyn Output> = if opt.json {
         Box::new(Json)
@@ -150,32 +198,72 @@ async fn run() -> Result<(), Error> {
                 .into_iter()
                 .map(|tag| Tag::try_from(tag).map_err(Error::from))
                 .collect::<Result<Vec<_>, Error>>()?;
-            let hash = try_hash(file)?;
-            client.add_tags_to_hash(&hash, tags).await?;
+            let prefix_hash = try_hash_prefix(&file, cfg.prefix_len, cfg.hash_kind)?;
+            let hash = try_hash(file, cfg.hash_kind)?;
+            client
+                .add_tags_to_hash(&hash, tags, Some(&prefix_hash))
+                .await?;
+        }
+
+        Subcmd::AddFilesTags { files, tags } => {
+            let tags = tags
+                .into_iter()
+                .map(|tag| Tag::try_from(tag).map_err(Error::from))
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            let entries = files
+                .into_par_iter()
+                .map(|file| {
+                    let prefix_hash = try_hash_prefix(&file, cfg.prefix_len, cfg.hash_kind)?;
+                    let hash = try_hash(file, cfg.hash_kind)?;
+                    Ok((hash, prefix_hash))
+                })
+                .collect::<Result<Vec<_>, Error>>()?
+                .into_iter()
+                .map(|(hash, prefix_hash)| (hash, tags.clone(), Some(prefix_hash)))
+                .collect();
+
+            client.add_tags_to_multiple(entries).await?;
         }
 
         Subcmd::CopyTags { src, dest } => {
+            // Computed from `dest` before it's consumed below, so `find_hashes_by_prefix` sees
+            // this hash too and a later `get-file-tags dest` can still take the screening
+            // shortcut.
+            let dest_prefix_hash = try_hash_prefix(&dest, cfg.prefix_len, cfg.hash_kind)?;
+
             // FIXME: is this even worth it?
             let mut hashes = vec![src, dest]
                 .into_par_iter()
-                .map(|path| try_hash(path))
+                .map(|path| try_hash(path, cfg.hash_kind))
                 .collect::<Result<Vec<_>, _>>()?;
 
             let dest_hash = hashes.pop().unwrap();
             let src_hash = hashes.pop().unwrap();
 
-            client.copy_tags(&src_hash, &dest_hash).await?;
+            client
+                .copy_tags(&src_hash, &dest_hash, Some(&dest_prefix_hash))
+                .await?;
         }
 
         Subcmd::GetFileTags { file } => {
-            let hash = try_hash(file)?;
-
-            let tags: Vec<_> = client
-                .get_tags(&hash)
-                .await?
-                .into_iter()
-                .map(|t| t.into_string())
-                .collect();
+            // Cheap prefix pass first: every path that ever attaches tags to a hash (both add
+            // commands and `copy-tags`) also records a prefix hash for it, so an empty result
+            // here really does mean the file is untagged and a full hash can be skipped.
+            let prefix_hash = try_hash_prefix(&file, cfg.prefix_len, cfg.hash_kind)?;
+            let candidates = client.find_hashes_by_prefix(&prefix_hash).await?;
+
+            let tags: Vec<_> = if candidates.is_empty() {
+                Vec::new()
+            } else {
+                let hash = try_hash(file, cfg.hash_kind)?;
+                client
+                    .get_tags(&hash)
+                    .await?
+                    .into_iter()
+                    .map(|t| t.into_string())
+                    .collect()
+            };
 
             output.file_tags(&tags, &mut stdout).unwrap();
         }
@@ -183,7 +271,7 @@ async fn run() -> Result<(), Error> {
         Subcmd::GetFilesTags { files } => {
             let file_hashes = files
                 .into_par_iter()
-                .filter_map(|file| match Blake2bHash::from_file(&file) {
+                .filter_map(|file| match Blake2bHash::from_file_with_kind(&file, cfg.hash_kind) {
                     Ok(hash) => Some((file, hash)),
                     Err(e) => {
                         eprintln!(
@@ -213,6 +301,34 @@ async fn run() -> Result<(), Error> {
 
             output.files_tags(&out, &mut stdout).unwrap();
         }
+
+        Subcmd::FindFiles { tags, match_all } => {
+            let tags = tags
+                .into_iter()
+                .map(|tag| Tag::try_from(tag).map_err(Error::from))
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            const PAGE_SIZE: u32 = 256;
+            let mut hashes = Vec::new();
+            let mut after = None;
+            loop {
+                let page = client
+                    .search_by_tags(tags.clone(), match_all, after.as_ref(), PAGE_SIZE)
+                    .await?;
+                match page.last() {
+                    Some(last) => after = Some(last.clone()),
+                    None => break,
+                }
+                let is_last_page = page.len() < PAGE_SIZE as usize;
+                hashes.extend(page);
+                if is_last_page {
+                    break;
+                }
+            }
+
+            let hashes: Vec<_> = hashes.into_iter().map(|hash| hash.to_string()).collect();
+            output.hashes(&hashes, &mut stdout).unwrap();
+        }
     }
 
     Ok(())
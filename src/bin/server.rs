@@ -1,65 +1,109 @@
-use std::{convert::TryFrom, sync::Arc};
+use std::{convert::TryFrom, pin::Pin, sync::Arc};
 
 use futures::prelude::*;
 use serde::Deserialize;
-use tgcd::raw::{server, AddTags, GetMultipleTagsReq, GetMultipleTagsResp, Hash, SrcDest, Tags};
+use structopt::StructOpt;
+use tgcd::raw::{
+    server, AddTags, AddTagsToMultipleReq, GetMultipleTagsReq, GetMultipleTagsResp, Hash, Hashes,
+    SearchByTagsReq, SearchByTagsResp, SrcDest, Tags,
+};
 use thiserror::Error;
-use tokio::sync::Mutex;
-use tokio_postgres as postgres;
 use tonic::{transport::Server, Request, Response, Status};
 
 use tgcd::{Blake2bHash, HashError, Tag, TagError};
 
+mod auth;
+mod store;
+
+use store::{Scope, Store, StoreError};
+
 #[derive(Deserialize)]
 struct Config {
-    postgres_url: String,
+    #[serde(default)]
+    backend: Backend,
+    postgres_url: Option<String>,
+    sqlite_path: Option<String>,
     port: u16,
+    #[serde(default = "default_pool_size")]
+    pool_size: usize,
+    #[serde(default)]
+    auth_enabled: bool,
 }
 
-#[derive(Clone)]
-struct Tgcd {
-    inner: Arc<TgcdInner>,
+fn default_pool_size() -> usize {
+    16
 }
 
-impl Tgcd {
-    async fn new(cfg: &Config) -> Result<Self, SetupError> {
-        let (mut client, connection) = postgres::connect(&cfg.postgres_url, postgres::NoTls)
-            .map_err(SetupError::PostgresConnect)
-            .await?;
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Backend {
+    Postgres,
+    Sqlite,
+    Memory,
+}
 
-        tokio::spawn(connection.map(|r| {
-            if let Err(e) = r {
-                log::error!("{}", e);
-            }
-        }));
-
-        let txn = client.transaction().await.unwrap();
-        let schema = include_str!("../../sql/schema.sql");
-        let _ = txn
-            .batch_execute(schema)
-            .map_err(SetupError::PostgresSchema)
-            .await;
-        txn.commit().await.unwrap();
-
-        Ok(Self {
-            inner: Arc::new(TgcdInner {
-                client: Mutex::new(client),
-            }),
-        })
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Postgres
     }
 }
 
-struct TgcdInner {
-    client: Mutex<postgres::Client>,
+#[derive(StructOpt)]
+struct Opt {
+    #[structopt(long)]
+    migrate_only: bool,
+
+    #[structopt(subcommand)]
+    cmd: Option<Cmd>,
+}
+
+#[derive(StructOpt)]
+enum Cmd {
+    CreateToken {
+        #[structopt(long)]
+        token: String,
+
+        #[structopt(long, possible_values = &["read", "write"])]
+        scope: String,
+
+        #[structopt(long)]
+        namespace_prefix: Option<String>,
+    },
+}
+
+struct Tgcd<S> {
+    store: Arc<S>,
+    auth_enabled: bool,
+}
+
+impl<S> Clone for Tgcd<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: Arc::clone(&self.store),
+            auth_enabled: self.auth_enabled,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum SetupError {
-    #[error("Can't connect to postgres: {0}")]
-    PostgresConnect(#[source] postgres::Error),
+    #[error("{0}")]
+    Store(#[from] StoreError),
 
-    #[error("Failed creating schema: {0}")]
-    PostgresSchema(#[source] postgres::Error),
+    #[error("`postgres_url` is required for the postgres backend")]
+    MissingPostgresUrl,
+
+    #[error("`sqlite_path` is required for the sqlite backend")]
+    MissingSqlitePath,
+
+    #[error(
+        "`auth_enabled` is set but tokens can't be provisioned for the {0} backend, so every \
+         request would be rejected as Unauthenticated"
+    )]
+    NoTokenStore(&'static str),
+
+    #[error("token provisioning is only supported for the postgres backend, not {0}")]
+    TokenProvisioningUnsupported(&'static str),
 
     #[error("Missing environment variable: {0}")]
     Env(#[from] envy::Error),
@@ -70,8 +114,8 @@ pub enum SetupError {
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Error from postgres: {0}")]
-    Postgres(#[from] postgres::Error),
+    #[error("{0}")]
+    Store(#[from] StoreError),
 
     #[error("Invalid hash: {0}")]
     ArgHash(HashError),
@@ -83,7 +127,7 @@ pub enum Error {
 impl From<Error> for Status {
     fn from(other: Error) -> Self {
         match other {
-            Error::Postgres(_) => Status::new(tonic::Code::Unavailable, "db error"),
+            Error::Store(_) => Status::new(tonic::Code::Unavailable, "store error"),
             Error::ArgHash(_) | Error::ArgTag(_) => {
                 Status::new(tonic::Code::InvalidArgument, "Received invalid argument")
             }
@@ -91,125 +135,154 @@ impl From<Error> for Status {
     }
 }
 
-async fn get_tags(client: &postgres::Client, hash: &Blake2bHash) -> Result<Vec<String>, Error> {
-    let stmnt = client
-        .prepare(
-            "
-        SELECT tag.name
-        FROM tag tag, hash_tag hash_tag, hash hash
-        WHERE
-            tag.id = hash_tag.tag_id
-            AND hash_tag.hash_id = hash.id
-            AND hash.hash = $1",
-        )
-        .await?;
-    let tags = client.query(&stmnt, &[&hash.as_ref()]).await?;
-    Ok(tags.into_iter().map(|row| row.get(0)).collect())
-}
-
-async fn get_or_insert_hash(
-    client: &postgres::Transaction<'_>,
-    hash: &Blake2bHash,
-) -> Result<i32, Error> {
-    let stmnt = client
-        .prepare(
-            "
-    WITH inserted AS (
-        INSERT INTO hash(hash)
-        VALUES($1)
-        ON CONFLICT DO NOTHING
-        RETURNING id
-    )
-    SELECT * FROM inserted
-
-    UNION ALL
-
-    SELECT id FROM hash
-    WHERE hash = $1
-    ",
-        )
-        .await?;
-
-    let row = client.query_one(&stmnt, &[&hash.as_ref()]).await?;
-
-    Ok(row.get(0))
-}
-
-async fn get_or_insert_tag(txn: &postgres::Transaction<'_>, tag: &str) -> Result<i32, Error> {
-    let stmnt = txn
-        .prepare(
-            "
-    WITH inserted AS (
-        INSERT INTO tag(name)
-        VALUES($1)
-        ON CONFLICT DO NOTHING
-        RETURNING id
-    )
-    SELECT * FROM inserted
-
-    UNION ALL
-
-    SELECT id FROM tag
-    WHERE name = $1
-    ",
-        )
-        .await?;
-
-    let row = txn.query_one(&stmnt, &[&tag]).await?;
-
-    Ok(row.get(0))
-}
-
-async fn add_tags_to_hash(
-    txn: &postgres::Transaction<'_>,
-    hash: &Blake2bHash,
-    tags: &[Tag],
-) -> Result<(), Error> {
-    let hash_id = get_or_insert_hash(&txn, &hash).await?;
-    for tag in tags {
-        let tag_id = get_or_insert_tag(&txn, &tag).await?;
-        txn.execute(
-            "INSERT INTO hash_tag(tag_id, hash_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
-            &[&tag_id, &hash_id],
-        )
-        .await?;
-    }
-    Ok(())
-}
-
 #[tonic::async_trait]
-impl server::Tgcd for Tgcd {
+impl<S: Store> server::Tgcd for Tgcd<S> {
     async fn get_tags(&self, req: Request<Hash>) -> Result<Response<Tags>, Status> {
-        let client = self.inner.client.lock().await;
+        let token = auth::authorize(&*self.store, &req, self.auth_enabled, Scope::Read).await?;
+
         let hash = Blake2bHash::try_from(&*req.into_inner().hash).map_err(Error::ArgHash)?;
-        let tags = get_tags(&client, &hash).await?;
+        let tags = self.store.get_tags(&hash).await.map_err(Error::Store)?;
+        let tags = auth::filter_namespace(tags, token.as_ref());
 
         Ok(Response::new(Tags { tags }))
     }
 
     async fn add_tags_to_hash(&self, req: Request<AddTags>) -> Result<Response<()>, Status> {
-        let mut client = self.inner.client.lock().await;
-        let AddTags { hash, tags } = req.into_inner();
+        let token = auth::authorize(&*self.store, &req, self.auth_enabled, Scope::Write).await?;
+
+        let AddTags {
+            hash,
+            tags,
+            prefix_hash,
+        } = req.into_inner();
         let hash = Blake2bHash::try_from(&*hash).map_err(Error::ArgHash)?;
         let tags = tags
             .into_iter()
             .map(Tag::try_from)
             .collect::<Result<Vec<_>, _>>()
             .map_err(Error::ArgTag)?;
+        let prefix_hash = prefix_hash
+            .map(|bytes| Blake2bHash::try_from(&*bytes))
+            .transpose()
+            .map_err(Error::ArgHash)?;
 
-        let txn = client.transaction().map_err(Error::Postgres).await?;
-        add_tags_to_hash(&txn, &hash, &tags).await?;
+        if let Some(prefix) = token.and_then(|t| t.namespace_prefix) {
+            if let Some(tag) = tags.iter().find(|tag| !tag.as_ref().starts_with(&prefix)) {
+                return Err(Status::new(
+                    tonic::Code::PermissionDenied,
+                    format!(
+                        "token is restricted to the \"{}\" namespace, can't write tag \"{}\"",
+                        prefix,
+                        tag.as_ref()
+                    ),
+                ));
+            }
+        }
 
-        txn.commit().map_err(Error::Postgres).await?;
+        self.store
+            .add_tags_to_hash(&hash, &tags)
+            .await
+            .map_err(Error::Store)?;
+
+        if let Some(prefix_hash) = &prefix_hash {
+            self.store
+                .record_prefix_hash(&hash, prefix_hash)
+                .await
+                .map_err(Error::Store)?;
+        }
 
         Ok(Response::new(()))
     }
 
+    async fn add_tags_to_multiple(
+        &self,
+        req: Request<AddTagsToMultipleReq>,
+    ) -> Result<Response<()>, Status> {
+        let token = auth::authorize(&*self.store, &req, self.auth_enabled, Scope::Write).await?;
+        let prefix = token.and_then(|t| t.namespace_prefix);
+
+        let entries = req
+            .into_inner()
+            .entries
+            .into_iter()
+            .map(
+                |AddTags {
+                     hash,
+                     tags,
+                     prefix_hash,
+                 }| {
+                    let hash = Blake2bHash::try_from(&*hash).map_err(Error::ArgHash)?;
+                    let tags = tags
+                        .into_iter()
+                        .map(Tag::try_from)
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(Error::ArgTag)?;
+                    let prefix_hash = prefix_hash
+                        .map(|bytes| Blake2bHash::try_from(&*bytes))
+                        .transpose()
+                        .map_err(Error::ArgHash)?;
+
+                    if let Some(prefix) = &prefix {
+                        if let Some(tag) = tags.iter().find(|tag| !tag.as_ref().starts_with(prefix)) {
+                            return Err(Status::new(
+                                tonic::Code::PermissionDenied,
+                                format!(
+                                    "token is restricted to the \"{}\" namespace, can't write tag \"{}\"",
+                                    prefix,
+                                    tag.as_ref()
+                                ),
+                            ));
+                        }
+                    }
+
+                    Ok((hash, tags, prefix_hash))
+                },
+            )
+            .collect::<Result<Vec<_>, Status>>()?;
+
+        let to_store = entries
+            .iter()
+            .map(|(hash, tags, _)| (hash.clone(), tags.clone()))
+            .collect::<Vec<_>>();
+        self.store
+            .add_tags_to_multiple(&to_store)
+            .await
+            .map_err(Error::Store)?;
+
+        for (hash, _, prefix_hash) in &entries {
+            if let Some(prefix_hash) = prefix_hash {
+                self.store
+                    .record_prefix_hash(hash, prefix_hash)
+                    .await
+                    .map_err(Error::Store)?;
+            }
+        }
+
+        Ok(Response::new(()))
+    }
+
+    async fn find_hashes_by_prefix(&self, req: Request<Hash>) -> Result<Response<Hashes>, Status> {
+        auth::authorize(&*self.store, &req, self.auth_enabled, Scope::Read).await?;
+
+        let prefix = Blake2bHash::try_from(&*req.into_inner().hash).map_err(Error::ArgHash)?;
+        let hashes = self
+            .store
+            .find_hashes_by_prefix(&prefix)
+            .await
+            .map_err(Error::Store)?
+            .into_iter()
+            .map(|hash| hash.to_vec())
+            .collect();
+
+        Ok(Response::new(Hashes { hashes }))
+    }
+
     async fn get_multiple_tags(
         &self,
         req: Request<GetMultipleTagsReq>,
     ) -> Result<Response<GetMultipleTagsResp>, Status> {
-        let client = self.inner.client.lock().await;
+        let token = auth::authorize(&*self.store, &req, self.auth_enabled, Scope::Read).await?;
+
         let hashes = req.into_inner().hashes;
         let hashes = hashes
             .into_iter()
@@ -217,58 +290,235 @@ impl server::Tgcd for Tgcd {
             .collect::<Result<Vec<_>, _>>()
             .map_err(Error::ArgHash)?;
 
-        let tags = future::try_join_all(
-            hashes
-                .iter()
-                .map(|hash| get_tags(&client, &hash).map_ok(|tags| Tags { tags }))
-                .collect::<Vec<_>>(),
-        )
-        .await?;
+        let tags = self
+            .store
+            .get_multiple_tags(&hashes)
+            .await
+            .map_err(Error::Store)?
+            .into_iter()
+            .map(|tags| Tags {
+                tags: auth::filter_namespace(tags, token.as_ref()),
+            })
+            .collect();
 
         Ok(Response::new(GetMultipleTagsResp { tags }))
     }
 
     async fn copy_tags(&self, req: Request<SrcDest>) -> Result<Response<()>, Status> {
+        let token = auth::authorize(&*self.store, &req, self.auth_enabled, Scope::Write).await?;
+
         let SrcDest {
             src_hash,
             dest_hash,
+            dest_prefix_hash,
         } = req.into_inner();
-        let mut client = self.inner.client.lock().await;
 
         let src_hash = Blake2bHash::try_from(&*src_hash).map_err(Error::ArgHash)?;
         let dest_hash = Blake2bHash::try_from(&*dest_hash).map_err(Error::ArgHash)?;
+        let dest_prefix_hash = dest_prefix_hash
+            .map(|bytes| Blake2bHash::try_from(&*bytes))
+            .transpose()
+            .map_err(Error::ArgHash)?;
 
-        let src_tags = get_tags(&client, &src_hash)
-            .await?
-            .into_iter()
-            .map(|a| Tag::try_from(a).unwrap())
-            .collect::<Vec<_>>();
+        if let Some(prefix) = token.and_then(|t| t.namespace_prefix) {
+            let src_tags = self.store.get_tags(&src_hash).await.map_err(Error::Store)?;
+            if let Some(tag) = src_tags.iter().find(|tag| !tag.starts_with(&prefix)) {
+                return Err(Status::new(
+                    tonic::Code::PermissionDenied,
+                    format!(
+                        "token is restricted to the \"{}\" namespace, can't copy tag \"{}\"",
+                        prefix, tag
+                    ),
+                ));
+            }
+        }
 
-        let txn = client.transaction().await.map_err(Error::Postgres)?;
-        add_tags_to_hash(&txn, &dest_hash, &src_tags).await?;
-        txn.commit().await.map_err(Error::Postgres)?;
+        self.store
+            .copy_tags(&src_hash, &dest_hash)
+            .await
+            .map_err(Error::Store)?;
+
+        if let Some(dest_prefix_hash) = &dest_prefix_hash {
+            self.store
+                .record_prefix_hash(&dest_hash, dest_prefix_hash)
+                .await
+                .map_err(Error::Store)?;
+        }
 
         Ok(Response::new(()))
     }
+
+    type SubscribeTagsStream = Pin<Box<dyn Stream<Item = Result<Tags, Status>> + Send + 'static>>;
+
+    async fn subscribe_tags(
+        &self,
+        req: Request<Hash>,
+    ) -> Result<Response<Self::SubscribeTagsStream>, Status> {
+        let token = auth::authorize(&*self.store, &req, self.auth_enabled, Scope::Read).await?;
+        let namespace_prefix = token.and_then(|t| t.namespace_prefix);
+
+        let hash = Blake2bHash::try_from(&*req.into_inner().hash).map_err(Error::ArgHash)?;
+        let stream = self
+            .store
+            .subscribe_tags(&hash)
+            .await
+            .map_err(Error::Store)?
+            .map(move |res| {
+                res.map(|tags| Tags {
+                    tags: match &namespace_prefix {
+                        Some(prefix) => {
+                            tags.into_iter().filter(|tag| tag.starts_with(prefix)).collect()
+                        }
+                        None => tags,
+                    },
+                })
+                .map_err(Error::Store)
+                .map_err(Status::from)
+            });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn search_by_tags(
+        &self,
+        req: Request<SearchByTagsReq>,
+    ) -> Result<Response<SearchByTagsResp>, Status> {
+        let token = auth::authorize(&*self.store, &req, self.auth_enabled, Scope::Read).await?;
+
+        let SearchByTagsReq {
+            tags,
+            match_all,
+            after,
+            limit,
+        } = req.into_inner();
+
+        let tags = tags
+            .into_iter()
+            .map(Tag::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::ArgTag)?;
+        let after = after
+            .map(|hash| Blake2bHash::try_from(&*hash))
+            .transpose()
+            .map_err(Error::ArgHash)?;
+
+        if let Some(prefix) = token.and_then(|t| t.namespace_prefix) {
+            if let Some(tag) = tags.iter().find(|tag| !tag.as_ref().starts_with(&prefix)) {
+                return Err(Status::new(
+                    tonic::Code::PermissionDenied,
+                    format!(
+                        "token is restricted to the \"{}\" namespace, can't search for tag \"{}\"",
+                        prefix,
+                        tag.as_ref()
+                    ),
+                ));
+            }
+        }
+
+        let hashes = self
+            .store
+            .search_by_tags(&tags, match_all, after.as_ref(), limit as i64)
+            .await
+            .map_err(Error::Store)?;
+
+        Ok(Response::new(SearchByTagsResp {
+            hashes: hashes.into_iter().map(|hash| hash.to_vec()).collect(),
+        }))
+    }
 }
 
-async fn run() -> Result<(), SetupError> {
-    let config: Config = envy::from_env()?;
-    let addr = format!("0.0.0.0:{}", config.port).parse().unwrap();
-    let tgcd = Tgcd::new(&config).await?;
+async fn serve<S: Store>(store: S, port: u16, auth_enabled: bool) -> Result<(), SetupError> {
+    let addr = format!("0.0.0.0:{}", port).parse().unwrap();
+    let tgcd = Tgcd {
+        store: Arc::new(store),
+        auth_enabled,
+    };
 
     Server::builder()
-        .add_service(server::TgcdServer::new(tgcd))
+        .add_service(server::TgcdServer::with_interceptor(
+            tgcd,
+            auth::extract_token,
+        ))
         .serve(addr)
         .await?;
 
     Ok(())
 }
 
+async fn run(opt: Opt) -> Result<(), SetupError> {
+    let config: Config = envy::from_env()?;
+
+    match config.backend {
+        Backend::Postgres => {
+            let postgres_url = config.postgres_url.ok_or(SetupError::MissingPostgresUrl)?;
+
+            if let Some(Cmd::CreateToken {
+                token,
+                scope,
+                namespace_prefix,
+            }) = opt.cmd
+            {
+                let scope = if scope == "write" { Scope::Write } else { Scope::Read };
+                return store::postgres::create_token(
+                    &postgres_url,
+                    &token,
+                    scope,
+                    namespace_prefix.as_deref(),
+                )
+                .await
+                .map_err(SetupError::from);
+            }
+
+            if opt.migrate_only {
+                return store::postgres::run_migrations(&postgres_url)
+                    .await
+                    .map_err(SetupError::from);
+            }
+
+            let store = store::PostgresStore::connect(&postgres_url, config.pool_size).await?;
+            serve(store, config.port, config.auth_enabled).await
+        }
+        Backend::Sqlite => {
+            if config.auth_enabled {
+                return Err(SetupError::NoTokenStore("sqlite"));
+            }
+
+            if opt.cmd.is_some() {
+                return Err(SetupError::TokenProvisioningUnsupported("sqlite"));
+            }
+
+            if opt.migrate_only {
+                // sqlite applies its schema on connect; nothing separate to migrate.
+                return Ok(());
+            }
+
+            let sqlite_path = config.sqlite_path.ok_or(SetupError::MissingSqlitePath)?;
+            let store = store::SqliteStore::open(&sqlite_path).await?;
+            serve(store, config.port, config.auth_enabled).await
+        }
+        Backend::Memory => {
+            if config.auth_enabled {
+                return Err(SetupError::NoTokenStore("memory"));
+            }
+
+            if opt.cmd.is_some() {
+                return Err(SetupError::TokenProvisioningUnsupported("memory"));
+            }
+
+            if opt.migrate_only {
+                return Ok(());
+            }
+
+            serve(store::MemoryStore::default(), config.port, config.auth_enabled).await
+        }
+    }
+}
+
 fn main() {
     env_logger::init();
+    let opt = Opt::from_args();
     let rt = tokio::runtime::Runtime::new().unwrap();
-    if let Err(e) = rt.block_on(run()) {
+    if let Err(e) = rt.block_on(run(opt)) {
         eprintln!("{}", e);
         std::process::exit(1);
     }
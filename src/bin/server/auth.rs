@@ -0,0 +1,148 @@
+use tonic::{Request, Status};
+
+use crate::store::{Scope, Store, TokenInfo};
+
+#[derive(Clone)]
+pub struct BearerToken(pub Option<String>);
+
+pub fn extract_token(mut req: Request<()>) -> Result<Request<()>, Status> {
+    let token = req
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_owned);
+
+    req.extensions_mut().insert(BearerToken(token));
+
+    Ok(req)
+}
+
+pub async fn authorize<S: Store, T>(
+    store: &S,
+    req: &Request<T>,
+    auth_enabled: bool,
+    required: Scope,
+) -> Result<Option<TokenInfo>, Status> {
+    if !auth_enabled {
+        return Ok(None);
+    }
+
+    let token = req
+        .extensions()
+        .get::<BearerToken>()
+        .and_then(|bearer| bearer.0.as_deref())
+        .ok_or_else(|| Status::new(tonic::Code::Unauthenticated, "missing bearer token"))?;
+
+    let info = store
+        .lookup_token(token)
+        .await
+        .map_err(|_| Status::new(tonic::Code::Unavailable, "store error"))?
+        .ok_or_else(|| Status::new(tonic::Code::Unauthenticated, "unknown token"))?;
+
+    if required == Scope::Write && info.scope == Scope::Read {
+        return Err(Status::new(
+            tonic::Code::PermissionDenied,
+            "read-only token cannot perform write operations",
+        ));
+    }
+
+    Ok(Some(info))
+}
+
+pub fn filter_namespace(tags: Vec<String>, token: Option<&TokenInfo>) -> Vec<String> {
+    match token.and_then(|info| info.namespace_prefix.as_deref()) {
+        Some(prefix) => tags.into_iter().filter(|tag| tag.starts_with(prefix)).collect(),
+        None => tags,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn request_with_token(token: Option<&str>) -> Request<()> {
+        let mut req = Request::new(());
+        req.extensions_mut()
+            .insert(BearerToken(token.map(str::to_owned)));
+        req
+    }
+
+    #[tokio::test]
+    async fn disabled_auth_is_a_no_op() {
+        let store = MemoryStore::default();
+        let req = request_with_token(None);
+
+        let info = authorize(&store, &req, false, Scope::Write).await.unwrap();
+        assert!(info.is_none());
+    }
+
+    #[tokio::test]
+    async fn missing_bearer_token_is_rejected() {
+        let store = MemoryStore::default();
+        let req = request_with_token(None);
+
+        let err = authorize(&store, &req, true, Scope::Read).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn unknown_token_is_rejected() {
+        let store = MemoryStore::default();
+        let req = request_with_token(Some("nonexistent"));
+
+        let err = authorize(&store, &req, true, Scope::Read).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn read_only_token_cannot_satisfy_write_scope() {
+        let store = MemoryStore::default();
+        store.register_token(
+            "reader",
+            TokenInfo {
+                scope: Scope::Read,
+                namespace_prefix: None,
+            },
+        );
+        let req = request_with_token(Some("reader"));
+
+        let err = authorize(&store, &req, true, Scope::Write).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn write_token_satisfies_read_and_write_scopes() {
+        let store = MemoryStore::default();
+        store.register_token(
+            "writer",
+            TokenInfo {
+                scope: Scope::Write,
+                namespace_prefix: Some("me/".to_owned()),
+            },
+        );
+        let req = request_with_token(Some("writer"));
+
+        assert!(authorize(&store, &req, true, Scope::Read).await.unwrap().is_some());
+        let info = authorize(&store, &req, true, Scope::Write).await.unwrap().unwrap();
+        assert_eq!(info.namespace_prefix.as_deref(), Some("me/"));
+    }
+
+    #[test]
+    fn filter_namespace_is_a_no_op_without_a_restriction() {
+        let tags = vec!["me/a".to_owned(), "other/b".to_owned()];
+        assert_eq!(filter_namespace(tags.clone(), None), tags);
+    }
+
+    #[test]
+    fn filter_namespace_drops_tags_outside_the_prefix() {
+        let token = TokenInfo {
+            scope: Scope::Read,
+            namespace_prefix: Some("me/".to_owned()),
+        };
+        let tags = vec!["me/a".to_owned(), "other/b".to_owned()];
+
+        assert_eq!(filter_namespace(tags, Some(&token)), vec!["me/a".to_owned()]);
+    }
+}
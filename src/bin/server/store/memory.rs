@@ -0,0 +1,290 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, RwLock},
+};
+
+use dashmap::DashMap;
+use futures::prelude::*;
+use tgcd::{Blake2bHash, Tag};
+use tokio::sync::broadcast;
+
+use super::{ReapingReceiver, StoreError, TagStream, TokenInfo};
+
+#[derive(Default)]
+struct Inner {
+    tags: RwLock<BTreeMap<Blake2bHash, BTreeMap<String, String>>>,
+    subscribers: DashMap<Blake2bHash, broadcast::Sender<()>>,
+    tokens: DashMap<String, TokenInfo>,
+    prefixes: RwLock<BTreeMap<Blake2bHash, BTreeSet<Blake2bHash>>>,
+}
+
+impl Inner {
+    fn get_tags(&self, hash: &Blake2bHash) -> Vec<String> {
+        self.tags
+            .read()
+            .unwrap()
+            .get(hash)
+            .map(|tags| tags.values().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: Arc<Inner>,
+}
+
+impl MemoryStore {
+    pub fn register_token(&self, token: impl Into<String>, info: TokenInfo) {
+        self.inner.tokens.insert(token.into(), info);
+    }
+}
+
+#[tonic::async_trait]
+impl super::Store for MemoryStore {
+    async fn get_tags(&self, hash: &Blake2bHash) -> Result<Vec<String>, StoreError> {
+        Ok(self.inner.get_tags(hash))
+    }
+
+    async fn add_tags_to_hash(&self, hash: &Blake2bHash, tags: &[Tag]) -> Result<(), StoreError> {
+        {
+            let mut store = self.inner.tags.write().unwrap();
+            let entry = store.entry(hash.clone()).or_default();
+            for tag in tags {
+                entry
+                    .entry(tag.normalized_key().to_owned())
+                    .or_insert_with(|| tag.as_ref().to_owned());
+            }
+        }
+
+        if let Some(tx) = self.inner.subscribers.get(hash) {
+            let _ = tx.send(());
+        }
+
+        Ok(())
+    }
+
+    async fn search_by_tags(
+        &self,
+        tags: &[Tag],
+        match_all: bool,
+        after: Option<&Blake2bHash>,
+        limit: i64,
+    ) -> Result<Vec<Blake2bHash>, StoreError> {
+        if match_all && tags.is_empty() {
+            // `Iterator::all` on an empty iterator is vacuously true; match the SQL backends,
+            // whose tag filter over an empty list (`IN ()` / `= ANY('{}')`) matches nothing.
+            return Ok(Vec::new());
+        }
+
+        let store = self.inner.tags.read().unwrap();
+        let matches = |hash_tags: &BTreeMap<String, String>| {
+            if match_all {
+                tags.iter()
+                    .all(|tag| hash_tags.contains_key(tag.normalized_key()))
+            } else {
+                tags.iter()
+                    .any(|tag| hash_tags.contains_key(tag.normalized_key()))
+            }
+        };
+
+        Ok(store
+            .iter()
+            .filter(|(hash, _)| after.map_or(true, |after| *hash > after))
+            .filter(|(_, hash_tags)| matches(hash_tags))
+            .take(limit.max(0) as usize)
+            .map(|(hash, _)| hash.clone())
+            .collect())
+    }
+
+    async fn subscribe_tags(&self, hash: &Blake2bHash) -> Result<TagStream, StoreError> {
+        let receiver = self
+            .inner
+            .subscribers
+            .entry(hash.clone())
+            .or_insert_with(|| broadcast::channel(16).0)
+            .subscribe();
+
+        let cleanup_inner = Arc::clone(&self.inner);
+        let cleanup_hash = hash.clone();
+        let changes = ReapingReceiver::new(receiver, move || {
+            cleanup_inner
+                .subscribers
+                .remove_if(&cleanup_hash, |_, tx| tx.receiver_count() == 0);
+        });
+
+        let inner = Arc::clone(&self.inner);
+        let hash = hash.clone();
+
+        // Emit the current tags immediately, then again every time `changes` fires.
+        let stream = stream::once(future::ready(()))
+            .chain(stream::unfold(changes, |mut changes| async move {
+                // A lagged receiver still re-queries; only a closed channel ends the stream.
+                match changes.recv().await {
+                    Err(broadcast::error::RecvError::Closed) => None,
+                    Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) => Some(((), changes)),
+                }
+            }))
+            .map(move |()| Ok(inner.get_tags(&hash)));
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn record_prefix_hash(&self, hash: &Blake2bHash, prefix: &Blake2bHash) -> Result<(), StoreError> {
+        self.inner
+            .prefixes
+            .write()
+            .unwrap()
+            .entry(prefix.clone())
+            .or_default()
+            .insert(hash.clone());
+        Ok(())
+    }
+
+    async fn find_hashes_by_prefix(&self, prefix: &Blake2bHash) -> Result<Vec<Blake2bHash>, StoreError> {
+        Ok(self
+            .inner
+            .prefixes
+            .read()
+            .unwrap()
+            .get(prefix)
+            .map(|hashes| hashes.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn lookup_token(&self, token: &str) -> Result<Option<TokenInfo>, StoreError> {
+        Ok(self.inner.tokens.get(token).map(|info| info.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::store::Store;
+
+    fn hash(seed: u8) -> Blake2bHash {
+        Blake2bHash::from_read(&mut std::io::Cursor::new(vec![seed; 16])).unwrap()
+    }
+
+    fn tag(s: &str) -> Tag {
+        Tag::try_from(s.to_owned()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn add_tags_to_multiple_upserts_every_entry() {
+        let store = MemoryStore::default();
+        let (a, b) = (hash(1), hash(2));
+
+        store
+            .add_tags_to_multiple(&[
+                (a.clone(), vec![tag("a"), tag("shared")]),
+                (b.clone(), vec![tag("b"), tag("shared")]),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(store.get_tags(&a).await.unwrap().len(), 2);
+        assert_eq!(store.get_tags(&b).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn search_by_tags_match_all_vs_match_any() {
+        let store = MemoryStore::default();
+        let (a, b) = (hash(1), hash(2));
+
+        store.add_tags_to_hash(&a, &[tag("red"), tag("big")]).await.unwrap();
+        store.add_tags_to_hash(&b, &[tag("red")]).await.unwrap();
+
+        let any = store
+            .search_by_tags(&[tag("red"), tag("big")], false, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(any.len(), 2);
+
+        let all = store
+            .search_by_tags(&[tag("red"), tag("big")], true, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(all, vec![a]);
+    }
+
+    #[tokio::test]
+    async fn search_by_tags_match_all_with_no_tags_matches_nothing() {
+        let store = MemoryStore::default();
+        store.add_tags_to_hash(&hash(1), &[tag("red")]).await.unwrap();
+
+        let all = store.search_by_tags(&[], true, None, 10).await.unwrap();
+        assert_eq!(all, Vec::new());
+    }
+
+    #[tokio::test]
+    async fn search_by_tags_honors_after_and_limit() {
+        let store = MemoryStore::default();
+        let mut hashes: Vec<_> = (1..=3u8).map(hash).collect();
+        for h in &hashes {
+            store.add_tags_to_hash(h, &[tag("x")]).await.unwrap();
+        }
+        // `search_by_tags` orders results by hash, not insertion order.
+        hashes.sort();
+
+        let first_page = store.search_by_tags(&[tag("x")], false, None, 1).await.unwrap();
+        assert_eq!(first_page, vec![hashes[0].clone()]);
+
+        let second_page = store
+            .search_by_tags(&[tag("x")], false, Some(&first_page[0]), 10)
+            .await
+            .unwrap();
+        assert_eq!(second_page, vec![hashes[1].clone(), hashes[2].clone()]);
+    }
+
+    #[tokio::test]
+    async fn subscribers_are_reaped_once_the_last_receiver_drops() {
+        let store = MemoryStore::default();
+        let h = hash(1);
+
+        let first = store.subscribe_tags(&h).await.unwrap();
+        let second = store.subscribe_tags(&h).await.unwrap();
+        assert_eq!(store.inner.subscribers.len(), 1);
+
+        drop(first);
+        assert_eq!(
+            store.inner.subscribers.len(),
+            1,
+            "entry survives while a receiver is still subscribed"
+        );
+
+        drop(second);
+        assert_eq!(
+            store.inner.subscribers.len(),
+            0,
+            "entry is reaped once the last receiver disconnects"
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_tags_emits_current_tags_then_updates() {
+        let store = MemoryStore::default();
+        let h = hash(1);
+        store.add_tags_to_hash(&h, &[tag("initial")]).await.unwrap();
+
+        let mut stream = store.subscribe_tags(&h).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), vec!["initial".to_owned()]);
+
+        store.add_tags_to_hash(&h, &[tag("added")]).await.unwrap();
+        let tags = stream.next().await.unwrap().unwrap();
+        assert_eq!(tags.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn find_hashes_by_prefix_is_empty_until_recorded() {
+        let store = MemoryStore::default();
+        let (full, prefix) = (hash(1), hash(2));
+
+        assert!(store.find_hashes_by_prefix(&prefix).await.unwrap().is_empty());
+
+        store.record_prefix_hash(&full, &prefix).await.unwrap();
+        assert_eq!(store.find_hashes_by_prefix(&prefix).await.unwrap(), vec![full]);
+    }
+}
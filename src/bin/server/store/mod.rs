@@ -0,0 +1,152 @@
+use std::{convert::TryFrom, pin::Pin};
+
+use futures::Stream;
+use tgcd::{Blake2bHash, Tag};
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+pub use sqlite::SqliteStore;
+
+pub type TagStream = Pin<Box<dyn Stream<Item = Result<Vec<String>, StoreError>> + Send>>;
+
+pub(crate) struct ReapingReceiver {
+    receiver: Option<broadcast::Receiver<()>>,
+    on_drop: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl ReapingReceiver {
+    pub(crate) fn new(
+        receiver: broadcast::Receiver<()>,
+        on_drop: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        Self {
+            receiver: Some(receiver),
+            on_drop: Some(Box::new(on_drop)),
+        }
+    }
+
+    pub(crate) async fn recv(&mut self) -> Result<(), broadcast::error::RecvError> {
+        self.receiver
+            .as_mut()
+            .expect("receiver is only taken on drop")
+            .recv()
+            .await
+    }
+}
+
+impl Drop for ReapingReceiver {
+    fn drop(&mut self) {
+        self.receiver.take();
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop();
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenInfo {
+    pub scope: Scope,
+    pub namespace_prefix: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("Can't connect to postgres: {0}")]
+    PostgresConnect(#[source] tokio_postgres::Error),
+
+    #[error("Invalid postgres connection string: {0}")]
+    PostgresConfig(#[source] tokio_postgres::Error),
+
+    #[error("Can't build connection pool: {0}")]
+    PoolBuild(#[from] deadpool_postgres::BuildError),
+
+    #[error("Can't check out pooled connection: {0}")]
+    Pool(#[from] deadpool_postgres::PoolError),
+
+    #[error("Error from postgres: {0}")]
+    Postgres(#[from] tokio_postgres::Error),
+
+    #[error("Failed running migrations: {0}")]
+    Migration(#[source] refinery::Error),
+
+    #[error("Error from sqlite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[error("{0} backend does not support tag-change subscriptions")]
+    SubscribeUnsupported(&'static str),
+
+    #[error("Backend returned a malformed hash")]
+    MalformedHash,
+}
+
+#[tonic::async_trait]
+pub trait Store: Send + Sync + 'static {
+    async fn get_tags(&self, hash: &Blake2bHash) -> Result<Vec<String>, StoreError>;
+
+    async fn add_tags_to_hash(&self, hash: &Blake2bHash, tags: &[Tag]) -> Result<(), StoreError>;
+
+    async fn add_tags_to_multiple(&self, entries: &[(Blake2bHash, Vec<Tag>)]) -> Result<(), StoreError> {
+        for (hash, tags) in entries {
+            self.add_tags_to_hash(hash, tags).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_multiple_tags(
+        &self,
+        hashes: &[Blake2bHash],
+    ) -> Result<Vec<Vec<String>>, StoreError> {
+        let mut out = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            out.push(self.get_tags(hash).await?);
+        }
+        Ok(out)
+    }
+
+    async fn copy_tags(&self, src: &Blake2bHash, dest: &Blake2bHash) -> Result<(), StoreError> {
+        let tags = self
+            .get_tags(src)
+            .await?
+            .into_iter()
+            .map(|t| Tag::try_from(t).expect("tag read back from the store is always valid"))
+            .collect::<Vec<_>>();
+        self.add_tags_to_hash(dest, &tags).await
+    }
+
+    async fn search_by_tags(
+        &self,
+        tags: &[Tag],
+        match_all: bool,
+        after: Option<&Blake2bHash>,
+        limit: i64,
+    ) -> Result<Vec<Blake2bHash>, StoreError>;
+
+    async fn subscribe_tags(&self, hash: &Blake2bHash) -> Result<TagStream, StoreError>;
+
+    async fn record_prefix_hash(
+        &self,
+        hash: &Blake2bHash,
+        prefix: &Blake2bHash,
+    ) -> Result<(), StoreError>;
+
+    async fn find_hashes_by_prefix(
+        &self,
+        prefix: &Blake2bHash,
+    ) -> Result<Vec<Blake2bHash>, StoreError>;
+
+    async fn lookup_token(&self, _token: &str) -> Result<Option<TokenInfo>, StoreError> {
+        Ok(None)
+    }
+}
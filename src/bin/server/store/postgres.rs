@@ -0,0 +1,472 @@
+use std::{convert::TryFrom, sync::Arc};
+
+use dashmap::DashMap;
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
+use futures::prelude::*;
+use sha2::{Digest, Sha256};
+use tgcd::{Blake2bHash, Tag};
+use tokio::sync::broadcast;
+use tokio_postgres::{self as postgres, AsyncMessage};
+
+use super::{ReapingReceiver, Scope, StoreError, TagStream, TokenInfo};
+
+const TAG_CHANGE_CHANNEL: &str = "tgcd_tag_change";
+
+mod migrations {
+    refinery::embed_migrations!("migrations");
+}
+
+pub async fn run_migrations(postgres_url: &str) -> Result<(), StoreError> {
+    let (mut client, connection) = postgres::connect(postgres_url, postgres::NoTls)
+        .await
+        .map_err(StoreError::PostgresConnect)?;
+
+    tokio::spawn(connection.map(|r| {
+        if let Err(e) = r {
+            log::error!("{}", e);
+        }
+    }));
+
+    migrations::runner()
+        .run_async(&mut client)
+        .await
+        .map_err(StoreError::Migration)?;
+
+    Ok(())
+}
+
+pub async fn create_token(
+    postgres_url: &str,
+    token: &str,
+    scope: Scope,
+    namespace_prefix: Option<&str>,
+) -> Result<(), StoreError> {
+    let (client, connection) = postgres::connect(postgres_url, postgres::NoTls)
+        .await
+        .map_err(StoreError::PostgresConnect)?;
+
+    tokio::spawn(connection.map(|r| {
+        if let Err(e) = r {
+            log::error!("{}", e);
+        }
+    }));
+
+    let token_hash = hex::encode(Sha256::digest(token.as_bytes()));
+    let scope = match scope {
+        Scope::Read => "read",
+        Scope::Write => "write",
+    };
+
+    client
+        .execute(
+            "INSERT INTO token(token_hash, scope, namespace_prefix) VALUES ($1, $2, $3)
+             ON CONFLICT (token_hash) DO UPDATE SET scope = $2, namespace_prefix = $3",
+            &[&token_hash, &scope, &namespace_prefix],
+        )
+        .await?;
+
+    Ok(())
+}
+
+type SubscriberRegistry = DashMap<Blake2bHash, broadcast::Sender<()>>;
+
+pub struct PostgresStore {
+    pool: Pool,
+    subscribers: Arc<SubscriberRegistry>,
+}
+
+impl PostgresStore {
+    pub async fn connect(postgres_url: &str, pool_size: usize) -> Result<Self, StoreError> {
+        run_migrations(postgres_url).await?;
+
+        let pg_config = postgres_url
+            .parse::<postgres::Config>()
+            .map_err(StoreError::PostgresConfig)?;
+
+        let manager = Manager::from_config(
+            pg_config,
+            postgres::NoTls,
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            },
+        );
+        let pool = Pool::builder(manager)
+            .max_size(pool_size)
+            .build()
+            .map_err(StoreError::PoolBuild)?;
+
+        let subscribers = Arc::new(DashMap::new());
+        tokio::spawn(listen_for_tag_changes(
+            postgres_url.to_owned(),
+            Arc::clone(&subscribers),
+        ));
+
+        Ok(Self { pool, subscribers })
+    }
+}
+
+async fn listen_for_tag_changes(postgres_url: String, subscribers: Arc<SubscriberRegistry>) {
+    loop {
+        match postgres::connect(&postgres_url, postgres::NoTls).await {
+            Ok((client, mut connection)) => {
+                match client
+                    .batch_execute(&format!("LISTEN {}", TAG_CHANGE_CHANNEL))
+                    .await
+                {
+                    Ok(()) => {
+                        while let Some(msg) = future::poll_fn(|cx| connection.poll_message(cx)).await {
+                            match msg {
+                                Ok(AsyncMessage::Notification(notification)) => {
+                                    if let Ok(bytes) = hex::decode(notification.payload()) {
+                                        if let Ok(hash) = Blake2bHash::try_from(&*bytes) {
+                                            if let Some(tx) = subscribers.get(&hash) {
+                                                // No receivers left is not an error; a later
+                                                // subscriber starts by querying the current tags.
+                                                let _ = tx.send(());
+                                            }
+                                        }
+                                    }
+                                }
+                                Ok(_) => {}
+                                Err(e) => {
+                                    log::error!("tag change listener connection error: {}", e);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    // Falls through to the backoff sleep below, same as every other failure
+                    // path in this loop, instead of immediately retrying.
+                    Err(e) => log::error!("Failed to LISTEN for tag changes: {}", e),
+                }
+            }
+            Err(e) => {
+                log::error!("Can't connect tag change listener: {}", e);
+            }
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+}
+
+fn subscribe(subscribers: &Arc<SubscriberRegistry>, hash: &Blake2bHash) -> ReapingReceiver {
+    let receiver = subscribers
+        .entry(hash.clone())
+        .or_insert_with(|| broadcast::channel(16).0)
+        .subscribe();
+
+    let subscribers = Arc::clone(subscribers);
+    let hash = hash.clone();
+    ReapingReceiver::new(receiver, move || {
+        subscribers.remove_if(&hash, |_, tx| tx.receiver_count() == 0);
+    })
+}
+
+async fn get_tags(client: &deadpool_postgres::Client, hash: &Blake2bHash) -> Result<Vec<String>, StoreError> {
+    let stmnt = client
+        .prepare_cached(
+            "
+        SELECT tag.display_name
+        FROM tag tag, hash_tag hash_tag, hash hash
+        WHERE
+            tag.id = hash_tag.tag_id
+            AND hash_tag.hash_id = hash.id
+            AND hash.hash = $1",
+        )
+        .await?;
+    let tags = client.query(&stmnt, &[&hash.as_ref()]).await?;
+    Ok(tags.into_iter().map(|row| row.get(0)).collect())
+}
+
+async fn get_or_insert_hash(
+    txn: &deadpool_postgres::Transaction<'_>,
+    hash: &Blake2bHash,
+) -> Result<i32, StoreError> {
+    let stmnt = txn
+        .prepare_cached(
+            "
+    WITH inserted AS (
+        INSERT INTO hash(hash)
+        VALUES($1)
+        ON CONFLICT DO NOTHING
+        RETURNING id
+    )
+    SELECT * FROM inserted
+
+    UNION ALL
+
+    SELECT id FROM hash
+    WHERE hash = $1
+    ",
+        )
+        .await?;
+
+    let row = txn.query_one(&stmnt, &[&hash.as_ref()]).await?;
+
+    Ok(row.get(0))
+}
+
+async fn get_or_insert_tag(txn: &deadpool_postgres::Transaction<'_>, tag: &Tag) -> Result<i32, StoreError> {
+    let stmnt = txn
+        .prepare_cached(
+            "
+    WITH inserted AS (
+        INSERT INTO tag(display_name, normalized_name)
+        VALUES($1, $2)
+        ON CONFLICT (normalized_name) DO NOTHING
+        RETURNING id
+    )
+    SELECT * FROM inserted
+
+    UNION ALL
+
+    SELECT id FROM tag
+    WHERE normalized_name = $2
+    ",
+        )
+        .await?;
+
+    let row = txn
+        .query_one(&stmnt, &[&tag.as_ref(), &tag.normalized_key()])
+        .await?;
+
+    Ok(row.get(0))
+}
+
+#[tonic::async_trait]
+impl super::Store for PostgresStore {
+    async fn get_tags(&self, hash: &Blake2bHash) -> Result<Vec<String>, StoreError> {
+        let client = self.pool.get().await?;
+        get_tags(&client, hash).await
+    }
+
+    async fn add_tags_to_hash(&self, hash: &Blake2bHash, tags: &[Tag]) -> Result<(), StoreError> {
+        let mut client = self.pool.get().await?;
+        let txn = client.transaction().await?;
+
+        let hash_id = get_or_insert_hash(&txn, hash).await?;
+        for tag in tags {
+            let tag_id = get_or_insert_tag(&txn, tag).await?;
+            txn.execute(
+                "INSERT INTO hash_tag(tag_id, hash_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                &[&tag_id, &hash_id],
+            )
+            .await?;
+        }
+
+        // Queued notifications are only delivered to listeners once this transaction commits,
+        // so subscribers never observe a change before it's actually visible.
+        txn.execute(
+            "SELECT pg_notify($1, $2)",
+            &[&TAG_CHANGE_CHANNEL, &hash.to_string()],
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    async fn add_tags_to_multiple(
+        &self,
+        entries: &[(Blake2bHash, Vec<Tag>)],
+    ) -> Result<(), StoreError> {
+        let mut client = self.pool.get().await?;
+        let txn = client.transaction().await?;
+
+        // Upsert each distinct tag once (by normalized key), even if several entries share it
+        // under different case/composition.
+        let mut tag_ids = std::collections::HashMap::new();
+        for (_, tags) in entries {
+            for tag in tags {
+                if !tag_ids.contains_key(tag.normalized_key()) {
+                    let id = get_or_insert_tag(&txn, tag).await?;
+                    tag_ids.insert(tag.normalized_key().to_owned(), id);
+                }
+            }
+        }
+
+        for (hash, tags) in entries {
+            let hash_id = get_or_insert_hash(&txn, hash).await?;
+            for tag in tags {
+                let tag_id = tag_ids[tag.normalized_key()];
+                txn.execute(
+                    "INSERT INTO hash_tag(tag_id, hash_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                    &[&tag_id, &hash_id],
+                )
+                .await?;
+            }
+            txn.execute(
+                "SELECT pg_notify($1, $2)",
+                &[&TAG_CHANGE_CHANNEL, &hash.to_string()],
+            )
+            .await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_multiple_tags(
+        &self,
+        hashes: &[Blake2bHash],
+    ) -> Result<Vec<Vec<String>>, StoreError> {
+        future::try_join_all(hashes.iter().map(|hash| async move {
+            let client = self.pool.get().await?;
+            get_tags(&client, hash).await
+        }))
+        .await
+    }
+
+    async fn copy_tags(&self, src: &Blake2bHash, dest: &Blake2bHash) -> Result<(), StoreError> {
+        let read_client = self.pool.get().await?;
+        let src_tags = get_tags(&read_client, src)
+            .await?
+            .into_iter()
+            .map(|a| Tag::try_from(a).expect("tag read back from postgres is always valid"))
+            .collect::<Vec<_>>();
+        drop(read_client);
+
+        self.add_tags_to_hash(dest, &src_tags).await
+    }
+
+    async fn search_by_tags(
+        &self,
+        tags: &[Tag],
+        match_all: bool,
+        after: Option<&Blake2bHash>,
+        limit: i64,
+    ) -> Result<Vec<Blake2bHash>, StoreError> {
+        let client = self.pool.get().await?;
+
+        let after_id: i32 = match after {
+            Some(hash) => {
+                let stmnt = client
+                    .prepare_cached("SELECT id FROM hash WHERE hash = $1")
+                    .await?;
+                client
+                    .query_opt(&stmnt, &[&hash.as_ref()])
+                    .await?
+                    .map_or(0, |row| row.get(0))
+            }
+            None => 0,
+        };
+        let tag_names: Vec<&str> = tags.iter().map(|t| t.normalized_key()).collect();
+
+        let query = if match_all {
+            "
+    SELECT hash.id, hash.hash
+    FROM hash
+    JOIN hash_tag ON hash_tag.hash_id = hash.id
+    JOIN tag ON tag.id = hash_tag.tag_id
+    WHERE tag.normalized_name = ANY($1) AND hash.id > $2
+    GROUP BY hash.id
+    HAVING count(DISTINCT tag.id) = $3
+    ORDER BY hash.id
+    LIMIT $4
+    "
+        } else {
+            "
+    SELECT DISTINCT hash.id, hash.hash
+    FROM hash
+    JOIN hash_tag ON hash_tag.hash_id = hash.id
+    JOIN tag ON tag.id = hash_tag.tag_id
+    WHERE tag.normalized_name = ANY($1) AND hash.id > $2
+    ORDER BY hash.id
+    LIMIT $3
+    "
+        };
+        let stmnt = client.prepare_cached(query).await?;
+
+        let rows = if match_all {
+            client
+                .query(&stmnt, &[&tag_names, &after_id, &(tags.len() as i64), &limit])
+                .await?
+        } else {
+            client.query(&stmnt, &[&tag_names, &after_id, &limit]).await?
+        };
+
+        rows.into_iter()
+            .map(|row| {
+                let hash: Vec<u8> = row.get(1);
+                Blake2bHash::try_from(&*hash).map_err(|_| StoreError::MalformedHash)
+            })
+            .collect()
+    }
+
+    async fn record_prefix_hash(&self, hash: &Blake2bHash, prefix: &Blake2bHash) -> Result<(), StoreError> {
+        let mut client = self.pool.get().await?;
+        let txn = client.transaction().await?;
+        let hash_id = get_or_insert_hash(&txn, hash).await?;
+
+        txn.execute(
+            "UPDATE hash SET prefix_hash = $2 WHERE id = $1",
+            &[&hash_id, &prefix.as_ref()],
+        )
+        .await?;
+
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn find_hashes_by_prefix(&self, prefix: &Blake2bHash) -> Result<Vec<Blake2bHash>, StoreError> {
+        let client = self.pool.get().await?;
+        let stmnt = client
+            .prepare_cached("SELECT hash FROM hash WHERE prefix_hash = $1")
+            .await?;
+        let rows = client.query(&stmnt, &[&prefix.as_ref()]).await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let hash: Vec<u8> = row.get(0);
+                Blake2bHash::try_from(&*hash).map_err(|_| StoreError::MalformedHash)
+            })
+            .collect()
+    }
+
+    async fn subscribe_tags(&self, hash: &Blake2bHash) -> Result<TagStream, StoreError> {
+        let changes = subscribe(&self.subscribers, hash);
+        let pool = self.pool.clone();
+        let hash = hash.clone();
+
+        // Emit the current tags immediately, then again every time `changes` fires.
+        let initial = stream::once(future::ready(()));
+        let stream = initial
+            .chain(stream::unfold(changes, |mut changes| async move {
+                // A lagged receiver still re-queries; only a closed channel ends the stream.
+                match changes.recv().await {
+                    Err(broadcast::error::RecvError::Closed) => None,
+                    Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) => Some(((), changes)),
+                }
+            }))
+            .then(move |()| {
+                let pool = pool.clone();
+                let hash = hash.clone();
+                async move {
+                    let client = pool.get().await?;
+                    get_tags(&client, &hash).await
+                }
+            });
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn lookup_token(&self, token: &str) -> Result<Option<TokenInfo>, StoreError> {
+        let client = self.pool.get().await?;
+        let token_hash = hex::encode(Sha256::digest(token.as_bytes()));
+
+        let stmnt = client
+            .prepare_cached("SELECT scope, namespace_prefix FROM token WHERE token_hash = $1")
+            .await?;
+        let row = client.query_opt(&stmnt, &[&token_hash]).await?;
+
+        Ok(row.map(|row| {
+            let scope: String = row.get(0);
+            TokenInfo {
+                scope: if scope == "write" { Scope::Write } else { Scope::Read },
+                namespace_prefix: row.get(1),
+            }
+        }))
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    convert::TryFrom,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use tgcd::{Blake2bHash, Tag};
+
+use super::{StoreError, TagStream};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS hash (
+    id INTEGER PRIMARY KEY,
+    hash BLOB NOT NULL UNIQUE,
+    prefix_hash BLOB
+);
+
+CREATE TABLE IF NOT EXISTS tag (
+    id INTEGER PRIMARY KEY,
+    display_name TEXT NOT NULL,
+    normalized_name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS hash_tag (
+    hash_id INTEGER NOT NULL REFERENCES hash (id),
+    tag_id INTEGER NOT NULL REFERENCES tag (id),
+    PRIMARY KEY (hash_id, tag_id)
+);
+";
+
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn blocking<F, T>(&self, f: F) -> Result<T, StoreError>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, StoreError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().expect("sqlite connection mutex poisoned");
+            f(&mut conn)
+        })
+        .await
+        .expect("sqlite worker thread panicked")
+    }
+}
+
+#[tonic::async_trait]
+impl super::Store for SqliteStore {
+    async fn get_tags(&self, hash: &Blake2bHash) -> Result<Vec<String>, StoreError> {
+        let hash = hash.clone();
+        self.blocking(move |conn| {
+            let mut stmnt = conn.prepare_cached(
+                "
+                SELECT tag.display_name
+                FROM tag, hash_tag, hash
+                WHERE
+                    tag.id = hash_tag.tag_id
+                    AND hash_tag.hash_id = hash.id
+                    AND hash.hash = ?1",
+            )?;
+            let tags = stmnt
+                .query_map(params![hash.as_ref()], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            Ok(tags)
+        })
+        .await
+    }
+
+    async fn add_tags_to_hash(&self, hash: &Blake2bHash, tags: &[Tag]) -> Result<(), StoreError> {
+        let hash = hash.clone();
+        let tags = tags.to_vec();
+        self.blocking(move |conn| {
+            let txn = conn.transaction()?;
+
+            txn.execute(
+                "INSERT OR IGNORE INTO hash(hash) VALUES (?1)",
+                params![hash.as_ref()],
+            )?;
+            let hash_id: i64 = txn.query_row(
+                "SELECT id FROM hash WHERE hash = ?1",
+                params![hash.as_ref()],
+                |row| row.get(0),
+            )?;
+
+            for tag in &tags {
+                txn.execute(
+                    "INSERT OR IGNORE INTO tag(display_name, normalized_name) VALUES (?1, ?2)",
+                    params![tag.as_ref(), tag.normalized_key()],
+                )?;
+                let tag_id: i64 = txn.query_row(
+                    "SELECT id FROM tag WHERE normalized_name = ?1",
+                    params![tag.normalized_key()],
+                    |row| row.get(0),
+                )?;
+                txn.execute(
+                    "INSERT OR IGNORE INTO hash_tag(tag_id, hash_id) VALUES (?1, ?2)",
+                    params![tag_id, hash_id],
+                )?;
+            }
+
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn search_by_tags(
+        &self,
+        tags: &[Tag],
+        match_all: bool,
+        after: Option<&Blake2bHash>,
+        limit: i64,
+    ) -> Result<Vec<Blake2bHash>, StoreError> {
+        let tags = tags.to_vec();
+        let after = after.cloned();
+        self.blocking(move |conn| {
+            let after_id: i64 = match &after {
+                Some(hash) => conn
+                    .query_row(
+                        "SELECT id FROM hash WHERE hash = ?1",
+                        params![hash.as_ref()],
+                        |row| row.get(0),
+                    )
+                    .optional()?
+                    .unwrap_or(0),
+                None => 0,
+            };
+
+            let placeholders = tags.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+            let query = if match_all {
+                format!(
+                    "
+        SELECT hash.id, hash.hash
+        FROM hash
+        JOIN hash_tag ON hash_tag.hash_id = hash.id
+        JOIN tag ON tag.id = hash_tag.tag_id
+        WHERE tag.normalized_name IN ({}) AND hash.id > ?
+        GROUP BY hash.id
+        HAVING count(DISTINCT tag.id) = {}
+        ORDER BY hash.id
+        LIMIT ?
+        ",
+                    placeholders,
+                    tags.len()
+                )
+            } else {
+                format!(
+                    "
+        SELECT DISTINCT hash.id, hash.hash
+        FROM hash
+        JOIN hash_tag ON hash_tag.hash_id = hash.id
+        JOIN tag ON tag.id = hash_tag.tag_id
+        WHERE tag.normalized_name IN ({}) AND hash.id > ?
+        ORDER BY hash.id
+        LIMIT ?
+        ",
+                    placeholders
+                )
+            };
+
+            let mut stmnt = conn.prepare_cached(&query)?;
+            let mut params: Vec<&dyn rusqlite::ToSql> = tags
+                .iter()
+                .map(|t| t.normalized_key() as &dyn rusqlite::ToSql)
+                .collect();
+            params.push(&after_id);
+            params.push(&limit);
+
+            let hashes = stmnt
+                .query_map(params.as_slice(), |row| row.get::<_, Vec<u8>>(1))?
+                .collect::<Result<Vec<Vec<u8>>, _>>()?;
+
+            hashes
+                .into_iter()
+                .map(|hash| Blake2bHash::try_from(&*hash).map_err(|_| StoreError::MalformedHash))
+                .collect()
+        })
+        .await
+    }
+
+    async fn record_prefix_hash(&self, hash: &Blake2bHash, prefix: &Blake2bHash) -> Result<(), StoreError> {
+        let hash = hash.clone();
+        let prefix = prefix.clone();
+        self.blocking(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO hash(hash) VALUES (?1)",
+                params![hash.as_ref()],
+            )?;
+            conn.execute(
+                "UPDATE hash SET prefix_hash = ?2 WHERE hash = ?1",
+                params![hash.as_ref(), prefix.as_ref()],
+            )?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_hashes_by_prefix(&self, prefix: &Blake2bHash) -> Result<Vec<Blake2bHash>, StoreError> {
+        let prefix = prefix.clone();
+        self.blocking(move |conn| {
+            let mut stmnt = conn.prepare_cached("SELECT hash FROM hash WHERE prefix_hash = ?1")?;
+
+            let hashes = stmnt
+                .query_map(params![prefix.as_ref()], |row| row.get::<_, Vec<u8>>(0))?
+                .collect::<Result<Vec<Vec<u8>>, _>>()?;
+
+            hashes
+                .into_iter()
+                .map(|hash| Blake2bHash::try_from(&*hash).map_err(|_| StoreError::MalformedHash))
+                .collect()
+        })
+        .await
+    }
+
+    async fn subscribe_tags(&self, _hash: &Blake2bHash) -> Result<TagStream, StoreError> {
+        Err(StoreError::SubscribeUnsupported("sqlite"))
+    }
+}
@@ -1,11 +1,15 @@
 use std::convert::TryFrom;
 
+use futures::Stream;
 use thiserror::Error;
 use tonic::Request;
 
 use crate::{config, raw, Blake2bHash, Tag};
 
-pub struct TgcdClient(raw::tgcd_client::TgcdClient<tonic::transport::Channel>);
+pub struct TgcdClient {
+    inner: raw::tgcd_client::TgcdClient<tonic::transport::Channel>,
+    token: Option<String>,
+}
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -15,6 +19,9 @@ pub enum Error {
     #[error("Server returned invalid Tag")]
     InvalidTag(#[from] crate::data::TagError),
 
+    #[error("Server returned invalid hash")]
+    InvalidHash(#[from] crate::data::HashError),
+
     #[error("Can't load global config: {0}")]
     Config(#[from] config::Error),
 
@@ -30,35 +37,76 @@ impl TgcdClient {
     {
         raw::tgcd_client::TgcdClient::connect(url)
             .await
-            .map(|c| Self(c))
+            .map(|inner| Self { inner, token: None })
     }
 
     pub async fn from_global_config() -> Result<Self, Error> {
         let cfg = config::Config::load().await?;
-        Self::connect(cfg.endpoint.into_string())
+        let client = Self::connect(cfg.endpoint.into_string())
             .await
-            .map_err(Error::Connect)
+            .map_err(Error::Connect)?;
+        Ok(match cfg.token {
+            Some(token) => client.with_token(token),
+            None => client,
+        })
+    }
+
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    fn request<T>(&self, msg: T) -> Request<T> {
+        let mut req = Request::new(msg);
+        if let Some(token) = &self.token {
+            if let Ok(value) = format!("Bearer {}", token).parse() {
+                req.metadata_mut().insert("authorization", value);
+            }
+        }
+        req
     }
 
     pub async fn add_tags_to_hash(
         &mut self,
         hash: &Blake2bHash,
         tags: Vec<Tag>,
+        prefix_hash: Option<&Blake2bHash>,
     ) -> Result<(), Error> {
-        self.0
-            .add_tags_to_hash(Request::new(raw::AddTags {
-                hash: hash.to_vec(),
-                tags: tags.into_iter().map(|s| s.into_string()).collect(),
-            }))
-            .await?;
+        let req = self.request(raw::AddTags {
+            hash: hash.to_vec(),
+            tags: tags.into_iter().map(|s| s.into_string()).collect(),
+            prefix_hash: prefix_hash.map(|hash| hash.to_vec()),
+        });
+        self.inner.add_tags_to_hash(req).await?;
         Ok(())
     }
 
+    pub async fn find_hashes_by_prefix(
+        &mut self,
+        prefix_hash: &Blake2bHash,
+    ) -> Result<Vec<Blake2bHash>, Error> {
+        let req = self.request(raw::Hash {
+            hash: prefix_hash.to_vec(),
+        });
+        let hashes = self
+            .inner
+            .find_hashes_by_prefix(req)
+            .await?
+            .into_inner()
+            .hashes;
+
+        hashes
+            .into_iter()
+            .map(|hash| Blake2bHash::try_from(&*hash).map_err(Error::from))
+            .collect()
+    }
+
     pub async fn get_tags(&mut self, hash: &Blake2bHash) -> Result<Vec<Tag>, Error> {
-        self.0
-            .get_tags(Request::new(raw::Hash {
-                hash: hash.to_vec(),
-            }))
+        let req = self.request(raw::Hash {
+            hash: hash.to_vec(),
+        });
+        self.inner
+            .get_tags(req)
             .await
             .map_err(Error::from)
             .and_then(|resp| {
@@ -74,11 +122,12 @@ impl TgcdClient {
         &mut self,
         hashes: impl IntoIterator<Item = &Blake2bHash>,
     ) -> Result<Vec<Vec<Tag>>, Error> {
+        let req = self.request(raw::GetMultipleTagsReq {
+            hashes: hashes.into_iter().map(|hash| hash.to_vec()).collect(),
+        });
         let tags = self
-            .0
-            .get_multiple_tags(Request::new(raw::GetMultipleTagsReq {
-                hashes: hashes.into_iter().map(|hash| hash.to_vec()).collect(),
-            }))
+            .inner
+            .get_multiple_tags(req)
             .await?
             .into_inner()
             .tags;
@@ -93,14 +142,79 @@ impl TgcdClient {
             .collect()
     }
 
-    pub async fn copy_tags(&mut self, src: &Blake2bHash, dest: &Blake2bHash) -> Result<(), Error> {
-        self.0
-            .copy_tags(Request::new(raw::SrcDest {
-                src_hash: src.to_vec(),
-                dest_hash: dest.to_vec(),
-            }))
-            .await?;
+    pub async fn add_tags_to_multiple(
+        &mut self,
+        entries: Vec<(Blake2bHash, Vec<Tag>, Option<Blake2bHash>)>,
+    ) -> Result<(), Error> {
+        let req = self.request(raw::AddTagsToMultipleReq {
+            entries: entries
+                .into_iter()
+                .map(|(hash, tags, prefix_hash)| raw::AddTags {
+                    hash: hash.to_vec(),
+                    tags: tags.into_iter().map(|t| t.into_string()).collect(),
+                    prefix_hash: prefix_hash.map(|hash| hash.to_vec()),
+                })
+                .collect(),
+        });
+        self.inner.add_tags_to_multiple(req).await?;
+        Ok(())
+    }
+
+    pub async fn copy_tags(
+        &mut self,
+        src: &Blake2bHash,
+        dest: &Blake2bHash,
+        dest_prefix_hash: Option<&Blake2bHash>,
+    ) -> Result<(), Error> {
+        let req = self.request(raw::SrcDest {
+            src_hash: src.to_vec(),
+            dest_hash: dest.to_vec(),
+            dest_prefix_hash: dest_prefix_hash.map(|hash| hash.to_vec()),
+        });
+        self.inner.copy_tags(req).await?;
 
         Ok(())
     }
+
+    pub async fn search_by_tags(
+        &mut self,
+        tags: Vec<Tag>,
+        match_all: bool,
+        after: Option<&Blake2bHash>,
+        limit: u32,
+    ) -> Result<Vec<Blake2bHash>, Error> {
+        let req = self.request(raw::SearchByTagsReq {
+            tags: tags.into_iter().map(|t| t.into_string()).collect(),
+            match_all,
+            after: after.map(|hash| hash.to_vec()),
+            limit,
+        });
+        let hashes = self.inner.search_by_tags(req).await?.into_inner().hashes;
+
+        hashes
+            .into_iter()
+            .map(|hash| Blake2bHash::try_from(&*hash).map_err(Error::from))
+            .collect()
+    }
+
+    pub async fn subscribe_tags(
+        &mut self,
+        hash: &Blake2bHash,
+    ) -> Result<impl Stream<Item = Result<Vec<Tag>, Error>>, Error> {
+        use futures::StreamExt;
+
+        let req = self.request(raw::Hash {
+            hash: hash.to_vec(),
+        });
+        let stream = self.inner.subscribe_tags(req).await?.into_inner();
+
+        Ok(stream.map(|res| {
+            res.map_err(Error::from).and_then(|tags| {
+                tags.tags
+                    .into_iter()
+                    .map(|t| Tag::try_from(t).map_err(Error::from))
+                    .collect()
+            })
+        }))
+    }
 }
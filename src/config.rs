@@ -3,6 +3,8 @@ use std::path::PathBuf;
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Config {
     pub endpoint: url::Url,
+    #[serde(default)]
+    pub token: Option<String>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -6,14 +6,18 @@ use std::{
     path::Path,
 };
 use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Debug, Clone)]
-pub struct Tag(String);
+pub struct Tag {
+    display: String,
+    normalized: String,
+}
 
 impl Deref for Tag {
     type Target = String;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.display
     }
 }
 
@@ -30,7 +34,11 @@ impl TryFrom<String> for Tag {
     fn try_from(other: String) -> Result<Self, Self::Error> {
         let len = other.chars().count();
         if len > 0 && len < 256 {
-            Ok(Tag(other))
+            let normalized = other.nfc().collect::<String>().to_lowercase();
+            Ok(Tag {
+                display: other,
+                normalized,
+            })
         } else {
             Err(TagError { len, s: other })
         }
@@ -39,42 +47,145 @@ impl TryFrom<String> for Tag {
 
 impl Tag {
     pub fn into_string(self) -> String {
-        self.0
+        self.display
+    }
+
+    pub fn normalized_key(&self) -> &str {
+        &self.normalized
     }
 }
 
 impl AsRef<str> for Tag {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.display
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashKind {
+    Blake2b,
+    Blake3,
+    Xxh3,
+    Crc32,
+}
+
+impl Default for HashKind {
+    fn default() -> Self {
+        HashKind::Blake2b
+    }
+}
+
+impl HashKind {
+    fn digest_len(self) -> usize {
+        match self {
+            HashKind::Blake2b => 64,
+            HashKind::Blake3 => 32,
+            HashKind::Xxh3 => 8,
+            HashKind::Crc32 => 4,
+        }
+    }
+
+    fn from_digest_len(len: usize) -> Option<Self> {
+        match len {
+            64 => Some(HashKind::Blake2b),
+            32 => Some(HashKind::Blake3),
+            8 => Some(HashKind::Xxh3),
+            4 => Some(HashKind::Crc32),
+            _ => None,
+        }
+    }
+
+    fn hasher(self) -> Box<dyn Hasher> {
+        match self {
+            HashKind::Blake2b => Box::new(blake2b_simd::State::new()),
+            HashKind::Blake3 => Box::new(blake3::Hasher::new()),
+            HashKind::Xxh3 => Box::new(xxhash_rust::xxh3::Xxh3::new()),
+            HashKind::Crc32 => Box::new(crc32fast::Hasher::new()),
+        }
+    }
+}
+
+trait Hasher {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self: Box<Self>) -> Box<[u8]>;
+}
+
+impl Hasher for blake2b_simd::State {
+    fn update(&mut self, data: &[u8]) {
+        blake2b_simd::State::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Box<[u8]> {
+        self.finalize().as_bytes().to_vec().into_boxed_slice()
+    }
+}
+
+impl Hasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Box<[u8]> {
+        self.finalize().as_bytes().to_vec().into_boxed_slice()
+    }
+}
+
+impl Hasher for xxhash_rust::xxh3::Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        xxhash_rust::xxh3::Xxh3::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Box<[u8]> {
+        self.digest().to_be_bytes().to_vec().into_boxed_slice()
+    }
+}
+
+impl Hasher for crc32fast::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        crc32fast::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Box<[u8]> {
+        self.finalize().to_be_bytes().to_vec().into_boxed_slice()
     }
 }
 
-#[derive(Clone)]
-pub struct Blake2bHash(Box<[u8; 64]>);
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Blake2bHash {
+    kind: HashKind,
+    bytes: Box<[u8]>,
+}
 
 impl Display for Blake2bHash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        f.write_str(&hex::encode(&self.0[..]))
+        f.write_str(&hex::encode(&self.bytes))
     }
 }
 
 impl Deref for Blake2bHash {
     type Target = [u8];
     fn deref(&self) -> &Self::Target {
-        &*self.0
+        &self.bytes
     }
 }
 
 impl AsRef<[u8]> for Blake2bHash {
     fn as_ref(&self) -> &[u8] {
-        &*self.0
+        &self.bytes
     }
 }
 
 #[derive(Error, Debug)]
-#[error("Invalid hash, must be exactly 64 byte long, is {len}")]
-pub struct HashError {
-    len: usize,
+pub enum HashError {
+    #[error("Invalid hash, length {len} doesn't match any known hash kind")]
+    InvalidLength { len: usize },
+
+    #[error("Invalid hex in hash string: {0}")]
+    InvalidHex(#[from] hex::FromHexError),
+
+    #[error("Invalid base58 in hash string: {0}")]
+    InvalidBase58(#[from] bs58::decode::Error),
 }
 
 impl TryFrom<&[u8]> for Blake2bHash {
@@ -82,39 +193,154 @@ impl TryFrom<&[u8]> for Blake2bHash {
 
     fn try_from(other: &[u8]) -> Result<Self, Self::Error> {
         let len = other.len();
-        let mut buf = [0; 64];
-        if len == 64 {
-            buf.copy_from_slice(other);
-            Ok(Self(Box::new(buf)))
-        } else {
-            Err(HashError { len })
+        match HashKind::from_digest_len(len) {
+            Some(kind) => Ok(Self {
+                kind,
+                bytes: other.to_vec().into_boxed_slice(),
+            }),
+            None => Err(HashError::InvalidLength { len }),
         }
     }
 }
 
+impl std::str::FromStr for Blake2bHash {
+    type Err = HashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s)?;
+        Blake2bHash::try_from(&*bytes)
+    }
+}
+
+pub const DEFAULT_PREFIX_LEN: u64 = 1024 * 1024;
+
 impl Blake2bHash {
+    pub fn kind(&self) -> HashKind {
+        self.kind
+    }
+
     pub fn from_read(r: &mut dyn Read) -> Result<Blake2bHash, io::Error> {
-        let mut state = blake2b_simd::State::new();
+        Self::from_read_with_kind(r, HashKind::default())
+    }
+
+    pub fn from_read_with_kind(r: &mut dyn Read, kind: HashKind) -> Result<Blake2bHash, io::Error> {
+        let mut hasher = kind.hasher();
         let mut buf = [0; 8192];
         loop {
             match r.read(&mut buf)? {
                 0 => break,
                 n => {
-                    state.update(&buf[..n]);
+                    hasher.update(&buf[..n]);
                 }
             }
         }
-        let ret = Box::new(*state.finalize().as_array());
-        Ok(Self(ret))
+        let bytes = hasher.finalize();
+        debug_assert_eq!(bytes.len(), kind.digest_len());
+        Ok(Self { kind, bytes })
     }
 
     pub fn from_file<P>(path: P) -> Result<Blake2bHash, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_file_with_kind(path, HashKind::default())
+    }
+
+    pub fn from_file_with_kind<P>(path: P, kind: HashKind) -> Result<Blake2bHash, io::Error>
     where
         P: AsRef<Path>,
     {
         let mut fh = std::fs::File::open(path)?;
 
-        Self::from_read(&mut fh)
+        Self::from_read_with_kind(&mut fh, kind)
+    }
+
+    pub fn from_read_prefix(r: &mut dyn Read, limit: u64) -> Result<Blake2bHash, io::Error> {
+        Self::from_read_prefix_with_kind(r, limit, HashKind::default())
+    }
+
+    pub fn from_read_prefix_with_kind(
+        r: &mut dyn Read,
+        limit: u64,
+        kind: HashKind,
+    ) -> Result<Blake2bHash, io::Error> {
+        let mut hasher = kind.hasher();
+        let mut buf = [0; 8192];
+        let mut remaining = limit;
+
+        while remaining > 0 {
+            let want = buf.len().min(remaining as usize);
+            match r.read(&mut buf[..want])? {
+                0 => break,
+                n => {
+                    hasher.update(&buf[..n]);
+                    remaining -= n as u64;
+                }
+            }
+        }
+
+        Ok(Self {
+            kind,
+            bytes: hasher.finalize(),
+        })
+    }
+
+    pub fn from_file_prefix<P>(path: P, limit: u64) -> Result<Blake2bHash, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_file_prefix_with_kind(path, limit, HashKind::default())
+    }
+
+    pub fn from_file_prefix_with_kind<P>(
+        path: P,
+        limit: u64,
+        kind: HashKind,
+    ) -> Result<Blake2bHash, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let mut fh = std::fs::File::open(path)?;
+
+        Self::from_read_prefix_with_kind(&mut fh, limit, kind)
+    }
+
+    pub fn to_base58(&self) -> String {
+        bs58::encode(&self.bytes).into_string()
+    }
+
+    pub fn from_base58(s: &str) -> Result<Self, HashError> {
+        let bytes = bs58::decode(s).into_vec()?;
+        Self::try_from(&*bytes)
+    }
+
+    pub fn copy_and_hash(
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> io::Result<(u64, Blake2bHash)> {
+        let kind = HashKind::default();
+        let mut hasher = kind.hasher();
+        let mut buf = [0; 8192];
+        let mut written = 0u64;
+
+        loop {
+            match reader.read(&mut buf)? {
+                0 => break,
+                n => {
+                    writer.write_all(&buf[..n])?;
+                    hasher.update(&buf[..n]);
+                    written += n as u64;
+                }
+            }
+        }
+
+        Ok((
+            written,
+            Self {
+                kind,
+                bytes: hasher.finalize(),
+            },
+        ))
     }
 }
 
@@ -130,12 +356,87 @@ mod tests {
         assert_eq!(&hash.to_string(), real_input_hash);
     }
 
+    #[test]
+    fn prefix_hash_matches_full_hash_of_shorter_input() {
+        let input = vec![b'a'; 100];
+
+        let prefix = Blake2bHash::from_read_prefix(&mut std::io::Cursor::new(&input), 50).unwrap();
+        let full = Blake2bHash::from_read(&mut std::io::Cursor::new(&input[..50])).unwrap();
+        assert_eq!(prefix, full);
+
+        let prefix_longer_than_input =
+            Blake2bHash::from_read_prefix(&mut std::io::Cursor::new(&input), 1000).unwrap();
+        let full_input = Blake2bHash::from_read(&mut std::io::Cursor::new(&input)).unwrap();
+        assert_eq!(prefix_longer_than_input, full_input);
+    }
+
+    #[test]
+    fn copy_and_hash_copies_and_matches_from_read() {
+        let input = vec![b'a'; 8192 * 3 - 28];
+        let mut out = Vec::new();
+
+        let (written, hash) =
+            Blake2bHash::copy_and_hash(&mut std::io::Cursor::new(&input), &mut out).unwrap();
+
+        assert_eq!(written, input.len() as u64);
+        assert_eq!(out, input);
+        assert_eq!(hash, Blake2bHash::from_read(&mut std::io::Cursor::new(&input)).unwrap());
+    }
+
     #[test]
     fn try_from_hash() {
         assert!(Blake2bHash::try_from(&vec![0_u8; 64][..]).is_ok());
         assert!(Blake2bHash::try_from(&vec![0_u8; 20][..]).is_err())
     }
 
+    #[test]
+    fn from_str_round_trips_with_display() {
+        let hash = Blake2bHash::from_read(&mut std::io::Cursor::new(b"hello")).unwrap();
+        let s = hash.to_string();
+        assert_eq!(s.len(), 128);
+        assert_eq!(s.parse::<Blake2bHash>().unwrap(), hash);
+
+        assert!("not hex".parse::<Blake2bHash>().is_err());
+        assert!("abcd".parse::<Blake2bHash>().is_err());
+    }
+
+    #[test]
+    fn from_str_infers_non_default_hash_kind() {
+        let hash =
+            Blake2bHash::from_read_with_kind(&mut std::io::Cursor::new(b"hello"), HashKind::Crc32)
+                .unwrap();
+        let s = hash.to_string();
+        assert_eq!(s.len(), 8);
+        assert_eq!(s.parse::<Blake2bHash>().unwrap(), hash);
+    }
+
+    #[test]
+    fn base58_round_trips() {
+        let hash = Blake2bHash::from_read(&mut std::io::Cursor::new(b"hello")).unwrap();
+        let encoded = hash.to_base58();
+        assert_eq!(Blake2bHash::from_base58(&encoded).unwrap(), hash);
+    }
+
+    #[test]
+    fn try_from_hash_infers_kind_from_len() {
+        assert_eq!(
+            Blake2bHash::try_from(&vec![0_u8; 64][..]).unwrap().kind(),
+            HashKind::Blake2b
+        );
+        assert_eq!(
+            Blake2bHash::try_from(&vec![0_u8; 32][..]).unwrap().kind(),
+            HashKind::Blake3
+        );
+        assert_eq!(
+            Blake2bHash::try_from(&vec![0_u8; 8][..]).unwrap().kind(),
+            HashKind::Xxh3
+        );
+        assert_eq!(
+            Blake2bHash::try_from(&vec![0_u8; 4][..]).unwrap().kind(),
+            HashKind::Crc32
+        );
+    }
+
     #[test]
     fn try_from_tag() {
         assert!(Tag::try_from(String::from("")).is_err());
@@ -144,4 +445,18 @@ mod tests {
         assert!(Tag::try_from(std::iter::repeat('a').take(256).collect::<String>()).is_err());
         assert!(Tag::try_from(std::iter::repeat('a').take(1000).collect::<String>()).is_err());
     }
+
+    #[test]
+    fn normalized_key_unifies_case_and_composition_variants() {
+        let composed = Tag::try_from(String::from("Caf\u{e9}")).unwrap();
+        let decomposed = Tag::try_from(String::from("cafe\u{301}")).unwrap();
+        assert_eq!(composed.normalized_key(), decomposed.normalized_key());
+
+        let upper = Tag::try_from(String::from("Art")).unwrap();
+        let lower = Tag::try_from(String::from("art")).unwrap();
+        assert_eq!(upper.normalized_key(), lower.normalized_key());
+
+        // The original spelling is preserved for display.
+        assert_eq!(composed.into_string(), "Caf\u{e9}");
+    }
 }
@@ -7,4 +7,4 @@ pub mod raw {
 }
 
 pub use client::{Error, TgcdClient};
-pub use data::{Blake2bHash, HashError, Tag, TagError};
+pub use data::{Blake2bHash, HashError, HashKind, Tag, TagError, DEFAULT_PREFIX_LEN};